@@ -20,11 +20,9 @@
 //! let data_ptr = &list.data as *const i32;
 //! 
 //! // Get the container of `data_ptr`, ie the `ListNode` it was made within.
-//! // SAFETY: `data_ptr` is a valid pointer to the `data` field of a
-//! // `ListNode<i32>`. Additionally, `ListNode<i32>` is sized.
-//! let list_ptr = unsafe {
-//! 	container_of::container_of!(data_ptr, ListNode<i32>, data)
-//! };
+//! // Forming the pointer is safe; only dereferencing it would require upholding the
+//! // `# Safety` contract below.
+//! let list_ptr = container_of::container_of!(data_ptr, ListNode<i32>, data);
 //!
 //! // The resulting pointer is the same as if you just got it straight
 //! // from the containing structure.
@@ -34,28 +32,120 @@
 //! 
 //! # Safety
 //! The following are needed to ensure soundness:
-//! - The `$type` must be a sized struct that is `#[repr(C)]` (or `#[repr(packed)]`).
+//! - The `$type` must be a sized struct that is `#[repr(C)]` (or `#[repr(packed)]`) — except when
+//!   using [`container_of_unsized!`], whose `$type` is instead a `?Sized` struct with an unsized
+//!   trailing field; see that macro's docs for its own `?Sized` contract.
 //! - The `$ptr` must be a valid pointer to the `$field` field of a `$type`. More concretely, this
 //!   means that the `$ptr` must have originated from a valid `$type` struct.
 //!
 //! [`container_of`]: https://github.com/torvalds/linux/blob/f71077a4d84bbe8c7b91b7db7c4ef815755ac5e3/tools/include/linux/kernel.h#L33-L35
+//!
+//! [`container_of_unsized!`] additionally requires the nightly `ptr_metadata` feature, as it
+//! needs to read and rebuild pointer metadata for `?Sized` trailing fields.
+//!
+//! [`container_of_unsized!`]: crate::container_of_unsized
 
-pub use memoffset::offset_of;
+#![cfg_attr(test, feature(ptr_metadata))]
 
 /// The [`container_of`] macro.
 ///
 /// See the crate-level docs for more info and safety considerations.
 ///
+/// Note that the offset computation itself uses `wrapping_sub`, so forming the container pointer
+/// is always well-defined, even if `$ptr` is a dangling or not-yet-live sentinel (e.g. the head of
+/// an intrusive list). The `# Safety` contract above only needs to be upheld once the resulting
+/// pointer is dereferenced.
+///
+/// `$field` isn't limited to a single identifier: it also accepts a dotted path such as
+/// `a.b.c`, which is forwarded straight to [`core::mem::offset_of!`] to recover the container of
+/// a pointer into a nested subfield.
+///
 /// [`container_of`]: https://github.com/torvalds/linux/blob/f71077a4d84bbe8c7b91b7db7c4ef815755ac5e3/tools/include/linux/kernel.h#L33-L35
 #[macro_export]
 macro_rules! container_of {
-	($ptr:expr, $type:path, $field:ident) => {
+	($ptr:expr, $type:path, $($field:tt)*) => {
 		$ptr.cast::<u8>()
-			.sub($crate::offset_of!($type, $field))
+			.wrapping_sub(core::mem::offset_of!($type, $($field)*))
 			.cast::<$type>()
 	};
 }
 
+/// Implementation detail of [`container_of_ref!`], kept as a real generic function (rather than
+/// inline macro code) so that the (elided) lifetime of the returned reference is actually tied to
+/// the lifetime of `field_ref` by the type system, instead of being silently discarded by a trip
+/// through a raw pointer. Not part of the public API.
+#[doc(hidden)]
+pub unsafe fn __container_of_ref<F: ?Sized, T>(field_ref: &F, offset: usize) -> &T {
+	let field_ptr = field_ref as *const F as *const u8;
+	unsafe { &*field_ptr.wrapping_sub(offset).cast::<T>() }
+}
+
+/// Implementation detail of [`container_of_mut!`]; see [`__container_of_ref`]. Not part of the
+/// public API.
+#[doc(hidden)]
+pub unsafe fn __container_of_mut<F: ?Sized, T>(field_ref: &mut F, offset: usize) -> &mut T {
+	let field_ptr = field_ref as *mut F as *mut u8;
+	unsafe { &mut *field_ptr.wrapping_sub(offset).cast::<T>() }
+}
+
+/// A reference-returning counterpart of [`container_of!`] that takes a `&$field` and returns a
+/// `&$type`, with the borrow's lifetime tied to the input reference's lifetime by
+/// [`__container_of_ref`]'s signature.
+///
+/// This is still `unsafe` to invoke: the obligation that the reference really did come from a
+/// live `$type` remains, but it eliminates the manual `as *const _` cast and dereference that
+/// [`container_of!`] otherwise requires at every call site.
+///
+/// See the crate-level docs for more info and safety considerations.
+#[macro_export]
+macro_rules! container_of_ref {
+	($field_ref:expr, $type:path, $($field:tt)*) => {
+		$crate::__container_of_ref::<_, $type>($field_ref, core::mem::offset_of!($type, $($field)*))
+	};
+}
+
+/// A reference-returning counterpart of [`container_of!`] that takes a `&mut $field` and returns a
+/// `&mut $type`, with the borrow's lifetime tied to the input reference's lifetime by
+/// [`__container_of_mut`]'s signature.
+///
+/// This is still `unsafe` to invoke: the obligation that the reference really did come from a
+/// live `$type` remains, but it eliminates the manual `as *mut _` cast and dereference that
+/// [`container_of!`] otherwise requires at every call site.
+///
+/// See the crate-level docs for more info and safety considerations.
+#[macro_export]
+macro_rules! container_of_mut {
+	($field_ref:expr, $type:path, $($field:tt)*) => {
+		$crate::__container_of_mut::<_, $type>($field_ref, core::mem::offset_of!($type, $($field)*))
+	};
+}
+
+/// Like [`container_of!`], but for recovering the container of a `?Sized` struct through its
+/// unsized trailing field.
+///
+/// `$field` must be the *final* field of `$type` (the one holding its pointer metadata), and all
+/// preceding fields must be `Sized`, so that the byte offset of `$field` is well-defined. Because
+/// `$type` and `$field` then share the exact same pointer metadata (e.g. a slice length, or a
+/// `str`'s byte length), the metadata is read off of `$ptr` and transferred verbatim onto the
+/// reconstructed container pointer; only the thin data address needs adjusting by the offset.
+///
+/// `core::mem::offset_of!` can't compute that offset directly, since it refuses to run on a
+/// field of a `?Sized` type. Instead this builds a dangling `$type` pointer that carries `$ptr`'s
+/// real metadata, and measures the offset of `$field` within *that*, the same "offset from a
+/// null-based pointer" trick the C `offsetof` macro itself relies on.
+///
+/// See the crate-level docs for more info and safety considerations.
+#[macro_export]
+macro_rules! container_of_unsized {
+	($ptr:expr, $type:path, $field:ident) => {{
+		let metadata = core::ptr::metadata($ptr);
+		let dangling: *const $type = core::ptr::from_raw_parts(core::ptr::null::<()>(), metadata);
+		let field_offset = core::ptr::addr_of!((*dangling).$field) as *const u8 as usize;
+		let data_ptr = ($ptr as *const u8).wrapping_sub(field_offset);
+		core::ptr::from_raw_parts::<$type>(data_ptr as *const (), metadata)
+	}};
+}
+
 #[cfg(test)]
 mod tests {
 	#[allow(unused)]
@@ -71,14 +161,10 @@ mod tests {
 		let mut wrap = Wrapper { foo: 1234, bar: 56, inner: 78u8 };
 
 		let inner_ptr = &wrap.inner as *const u8;
-		let _: *const Wrapper<u8> = unsafe {
-			crate::container_of!(inner_ptr, Wrapper<u8>, inner)
-		};
+		let _: *const Wrapper<u8> = crate::container_of!(inner_ptr, Wrapper<u8>, inner);
 
 		let inner_ptr_mut = &mut wrap.inner as *mut u8;
-		let _: *mut Wrapper<u8> = unsafe {
-			crate::container_of!(inner_ptr_mut, Wrapper<u8>, inner)
-		};
+		let _: *mut Wrapper<u8> = crate::container_of!(inner_ptr_mut, Wrapper<u8>, inner);
 	}
 
 	#[test]
@@ -86,9 +172,7 @@ mod tests {
 		let wrap = Wrapper { foo: 1234, bar: 56, inner: 78u8 };
 
 		let inner_ptr = &wrap.inner as *const u8;
-		let wrap_ptr = unsafe {
-			crate::container_of!(inner_ptr, Wrapper<u8>, inner)
-		};
+		let wrap_ptr = crate::container_of!(inner_ptr, Wrapper<u8>, inner);
 
 		assert_eq!(&wrap as *const Wrapper<u8>, wrap_ptr);
 	}
@@ -98,10 +182,126 @@ mod tests {
 		let wrap = Wrapper { foo: 1234, bar: 56, inner: 78i32 };
 
 		let inner_ptr = &wrap.inner as *const i32;
-		let wrap_ptr = unsafe {
-			crate::container_of!(inner_ptr, Wrapper<i32>, inner)
-		};
+		let wrap_ptr = crate::container_of!(inner_ptr, Wrapper<i32>, inner);
 
 		assert_eq!(&wrap as *const Wrapper<i32>, wrap_ptr);
 	}
+
+	#[allow(unused)]
+	#[repr(C)]
+	struct Middle {
+		pad: u8,
+		inner: Wrapper<i32>
+	}
+
+	#[allow(unused)]
+	#[repr(C)]
+	struct Outer {
+		pad: u16,
+		middle: Middle
+	}
+
+	#[test]
+	fn nested_two_levels() {
+		let outer = Outer {
+			pad: 1,
+			middle: Middle { pad: 2, inner: Wrapper { foo: 3, bar: 4, inner: 5 } }
+		};
+
+		let inner_ptr = &outer.middle.inner.inner as *const i32;
+		let outer_ptr = crate::container_of!(inner_ptr, Outer, middle.inner.inner);
+
+		assert_eq!(&outer as *const Outer, outer_ptr);
+	}
+
+	#[allow(unused)]
+	#[repr(C)]
+	struct Innermost {
+		pad: u8,
+		value: u64
+	}
+
+	#[allow(unused)]
+	#[repr(C)]
+	struct Layer2 {
+		pad: u16,
+		innermost: Innermost
+	}
+
+	#[allow(unused)]
+	#[repr(C)]
+	struct Layer1 {
+		pad: u8,
+		layer2: Layer2
+	}
+
+	#[test]
+	fn nested_three_levels() {
+		let layer1 = Layer1 {
+			pad: 9,
+			layer2: Layer2 { pad: 8, innermost: Innermost { pad: 7, value: 6 } }
+		};
+
+		let value_ptr = &layer1.layer2.innermost.value as *const u64;
+		let layer1_ptr = crate::container_of!(value_ptr, Layer1, layer2.innermost.value);
+
+		assert_eq!(&layer1 as *const Layer1, layer1_ptr);
+	}
+
+	#[test]
+	fn container_of_ref_returns_matching_reference() {
+		let wrap = Wrapper { foo: 1234, bar: 56, inner: 78i32 };
+
+		let wrap_ref: &Wrapper<i32> = unsafe {
+			crate::container_of_ref!(&wrap.inner, Wrapper<i32>, inner)
+		};
+
+		assert_eq!(wrap_ref as *const Wrapper<i32>, &wrap as *const Wrapper<i32>);
+	}
+
+	#[test]
+	fn container_of_mut_returns_matching_reference() {
+		let mut wrap = Wrapper { foo: 1234, bar: 56, inner: 78i32 };
+		let wrap_ptr = &wrap as *const Wrapper<i32>;
+
+		let wrap_ref: &mut Wrapper<i32> = unsafe {
+			crate::container_of_mut!(&mut wrap.inner, Wrapper<i32>, inner)
+		};
+
+		assert_eq!(wrap_ref as *mut Wrapper<i32>, wrap_ptr as *mut Wrapper<i32>);
+	}
+
+	#[test]
+	fn container_of_unsized_slice() {
+		let boxed: Box<Wrapper<[u8]>> = Box::new(Wrapper { foo: 11, bar: 22, inner: [1u8, 2, 3] });
+		let wrapper_ptr = Box::into_raw(boxed);
+
+		let inner_ptr = unsafe { &(*wrapper_ptr).inner as *const [u8] };
+		let recovered = unsafe {
+			crate::container_of_unsized!(inner_ptr, Wrapper<[u8]>, inner)
+		};
+
+		assert_eq!(recovered, wrapper_ptr as *const Wrapper<[u8]>);
+
+		unsafe { drop(Box::from_raw(wrapper_ptr)) };
+	}
+
+	#[test]
+	fn container_of_unsized_str() {
+		let boxed: Box<Wrapper<[u8]>> = Box::new(Wrapper { foo: 11, bar: 22, inner: *b"hiya" });
+
+		// SAFETY: `Wrapper<[u8]>` and `Wrapper<str>` have identical representations, and the
+		// bytes above are valid UTF-8, so reinterpreting the boxed `[u8]` as a `str` is sound.
+		let boxed: Box<Wrapper<str>> = unsafe { core::mem::transmute(boxed) };
+		let wrapper_ptr = Box::into_raw(boxed);
+
+		let inner_ptr = unsafe { &(*wrapper_ptr).inner as *const str };
+		let recovered = unsafe {
+			crate::container_of_unsized!(inner_ptr, Wrapper<str>, inner)
+		};
+
+		assert_eq!(recovered, wrapper_ptr as *const Wrapper<str>);
+
+		unsafe { drop(Box::from_raw(wrapper_ptr)) };
+	}
 }